@@ -0,0 +1,164 @@
+//! TTL-aware LRU cache of upstream answers, shared by every [`crate::dns_thread::DnsThread`]
+//! so repeated queries for the same name don't need a round trip to `icann_resolver`.
+
+use std::{
+    num::NonZeroUsize,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use lru::LruCache;
+use simple_dns::{Question, ResourceRecord, RCODE};
+
+/// `(lowercased QNAME, QTYPE, QCLASS)`
+type CacheKey = (String, u16, u16);
+
+#[derive(Debug, Clone)]
+struct CachedAnswer {
+    answers: Vec<ResourceRecord<'static>>,
+    stored_at: Instant,
+}
+
+/// Thread-safe, size-bounded cache keyed by question, evicting least-recently-used
+/// entries once full. Answers are returned with their TTL decremented by however
+/// long they've sat in the cache, and dropped once that TTL reaches zero.
+#[derive(Clone)]
+pub struct ThreadSafeCache {
+    inner: Arc<Mutex<LruCache<CacheKey, CachedAnswer>>>,
+}
+
+impl ThreadSafeCache {
+    /// Returns `None` if `capacity` is `0`, meaning caching is disabled.
+    pub fn new(capacity: usize) -> Option<Self> {
+        let capacity = NonZeroUsize::new(capacity)?;
+        Some(Self {
+            inner: Arc::new(Mutex::new(LruCache::new(capacity))),
+        })
+    }
+
+    fn key(question: &Question) -> CacheKey {
+        (
+            question.qname.to_string().to_lowercase(),
+            question.qtype.into(),
+            question.qclass.into(),
+        )
+    }
+
+    /// Returns unexpired answer records for `question`, if any are cached.
+    /// Evicts the entry once every record in it has expired.
+    pub fn get(&self, question: &Question) -> Option<Vec<ResourceRecord<'static>>> {
+        let key = Self::key(question);
+        let mut cache = self.inner.lock().unwrap();
+        let cached = cache.get(&key)?;
+        let elapsed = cached.stored_at.elapsed().as_secs() as u32;
+
+        let answers: Vec<ResourceRecord<'static>> = cached
+            .answers
+            .iter()
+            .filter_map(|record| {
+                let remaining_ttl = record.ttl.checked_sub(elapsed)?;
+                if remaining_ttl == 0 {
+                    return None;
+                }
+                let mut record = record.clone();
+                record.ttl = remaining_ttl;
+                Some(record)
+            })
+            .collect();
+
+        if answers.is_empty() {
+            cache.pop(&key);
+            None
+        } else {
+            Some(answers)
+        }
+    }
+
+    /// Stores `answers` for `question`, unless the upstream rcode wasn't NOERROR
+    /// or there's nothing to cache.
+    pub fn put(&self, question: &Question, rcode: RCODE, answers: &[ResourceRecord<'static>]) {
+        if rcode != RCODE::NoError || answers.is_empty() {
+            return;
+        }
+
+        let key = Self::key(question);
+        let cached = CachedAnswer {
+            answers: answers.to_vec(),
+            stored_at: Instant::now(),
+        };
+        self.inner.lock().unwrap().put(key, cached);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simple_dns::rdata::{RData, A};
+    use simple_dns::{Name, QCLASS, QTYPE, CLASS, TYPE};
+    use std::{net::Ipv4Addr, thread::sleep, time::Duration};
+
+    fn question(name: &str) -> Question<'static> {
+        Question::new(
+            Name::new(name).unwrap().into_owned(),
+            QTYPE::TYPE(TYPE::A),
+            QCLASS::CLASS(CLASS::IN),
+            false,
+        )
+    }
+
+    fn a_record(name: &str, ttl: u32) -> ResourceRecord<'static> {
+        ResourceRecord::new(
+            Name::new(name).unwrap().into_owned(),
+            CLASS::IN,
+            ttl,
+            RData::A(A { address: Ipv4Addr::new(1, 2, 3, 4).into() }),
+        )
+    }
+
+    #[test]
+    fn put_then_get_returns_the_cached_answer() {
+        let cache = ThreadSafeCache::new(10).unwrap();
+        let question = question("example.com");
+        cache.put(&question, RCODE::NoError, &[a_record("example.com", 300)]);
+
+        let answers = cache.get(&question).expect("entry should be cached");
+        assert_eq!(answers.len(), 1);
+    }
+
+    #[test]
+    fn entry_expires_once_its_ttl_elapses() {
+        let cache = ThreadSafeCache::new(10).unwrap();
+        let question = question("example.com");
+        cache.put(&question, RCODE::NoError, &[a_record("example.com", 1)]);
+
+        assert!(cache.get(&question).is_some(), "expected immediate cache hit");
+
+        sleep(Duration::from_millis(1100));
+        assert!(cache.get(&question).is_none(), "expected entry to expire after TTL elapsed");
+    }
+
+    #[test]
+    fn get_decrements_the_remaining_ttl_by_time_elapsed() {
+        let cache = ThreadSafeCache::new(10).unwrap();
+        let question = question("example.com");
+        cache.put(&question, RCODE::NoError, &[a_record("example.com", 10)]);
+
+        sleep(Duration::from_millis(1100));
+        let answers = cache.get(&question).expect("entry should still be cached");
+        assert!(answers[0].ttl < 10, "expected ttl to have decremented, got {}", answers[0].ttl);
+    }
+
+    #[test]
+    fn non_noerror_rcode_is_not_cached() {
+        let cache = ThreadSafeCache::new(10).unwrap();
+        let question = question("example.com");
+        cache.put(&question, RCODE::ServerFailure, &[a_record("example.com", 300)]);
+
+        assert!(cache.get(&question).is_none());
+    }
+
+    #[test]
+    fn zero_capacity_disables_caching() {
+        assert!(ThreadSafeCache::new(0).is_none());
+    }
+}
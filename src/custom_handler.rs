@@ -0,0 +1,84 @@
+//! The pluggable handler that gets the first chance to answer a query before
+//! any-dns falls back to ICANN.
+
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+/// Convenience alias for results returned by a [`CustomHandler`].
+pub type CustomHandlerResult<T> = std::result::Result<T, CustomHandlerError>;
+
+/// Error returned by a [`CustomHandler`] when it can't answer a query itself.
+/// any-dns will fall back to `icann_resolver` whenever this is returned.
+#[derive(Debug)]
+pub struct CustomHandlerError(String);
+
+impl CustomHandlerError {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self(message.into())
+    }
+}
+
+impl fmt::Display for CustomHandlerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for CustomHandlerError {}
+
+/// Implement this trait to answer queries yourself. Return `Err` to fall back to ICANN.
+pub trait CustomHandler: Send {
+    /// `query` is the raw, wire-format DNS query. `Ok` must contain a wire-format
+    /// reply with the same id as the query.
+    fn handle(&mut self, query: &[u8]) -> CustomHandlerResult<Vec<u8>>;
+}
+
+/// A [`CustomHandler`] that never answers and always falls back to ICANN.
+/// Used as the default handler when none is configured through [`crate::Builder::handler`].
+pub struct EmptyHandler;
+
+impl EmptyHandler {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl Default for EmptyHandler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CustomHandler for EmptyHandler {
+    fn handle(&mut self, _query: &[u8]) -> CustomHandlerResult<Vec<u8>> {
+        Err(CustomHandlerError::new("EmptyHandler never answers."))
+    }
+}
+
+/// Wraps a [`CustomHandler`] so it can be cloned into every [`crate::dns_thread::DnsThread`]
+/// while still being backed by a single, shared instance.
+pub struct HandlerHolder(Arc<Mutex<dyn CustomHandler>>);
+
+impl HandlerHolder {
+    pub fn new(handler: impl CustomHandler + 'static) -> Self {
+        Self(Arc::new(Mutex::new(handler)))
+    }
+
+    pub fn handle(&self, query: &[u8]) -> CustomHandlerResult<Vec<u8>> {
+        self.0.lock().unwrap().handle(query)
+    }
+}
+
+impl Clone for HandlerHolder {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl fmt::Debug for HandlerHolder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HandlerHolder").finish()
+    }
+}
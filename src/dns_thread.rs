@@ -0,0 +1,443 @@
+//! A single worker thread that owns a slice of the DNS packet id space, receives
+//! queries off the shared UDP socket and ICANN replies from its upstream reply
+//! channel, and answers clients.
+
+use std::{
+    fmt,
+    net::{SocketAddr, UdpSocket},
+    ops::Range,
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use simple_dns::{Packet, PacketFlag, RCODE};
+
+use crate::{
+    cache::ThreadSafeCache,
+    custom_handler::HandlerHolder,
+    metrics::Metrics,
+    pending_queries::{PendingQuery, ThreadSafeStore},
+    upstream::UpstreamResolver,
+    zones::{ZoneAnswer, ZoneStore},
+};
+
+/// Delay before the first retransmit of a query that got no answer from `icann_resolver`.
+const INITIAL_RETRANSMIT_DELAY: Duration = Duration::from_secs(1);
+/// Upper bound the retransmit delay backs off to, doubling each retry.
+const MAX_RETRANSMIT_DELAY: Duration = Duration::from_secs(10);
+
+/// Owns a slice of the 16-bit DNS packet id space (so replies coming back on the
+/// shared socket can be routed to the thread that forwarded the matching query)
+/// and drives the receive loop for that slice.
+pub struct DnsThread {
+    handle: Option<JoinHandle<()>>,
+    stop_signal_sender: Sender<()>,
+}
+
+impl DnsThread {
+    /// `upstream` is shared with every other [`DnsThread`] and the
+    /// [`crate::tcp::TcpDnsListener`] (one resolver, one underlying connection,
+    /// for the whole server); `upstream_replies` delivers only the replies for
+    /// this thread's slice of the id space, already demultiplexed by the caller.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        socket: &UdpSocket,
+        upstream: Arc<dyn UpstreamResolver>,
+        upstream_replies: Receiver<Vec<u8>>,
+        pending_queries: &ThreadSafeStore,
+        cache: &Option<ThreadSafeCache>,
+        zones: &ZoneStore,
+        id_range: Range<u16>,
+        handler: &HandlerHolder,
+        metrics: &Metrics,
+        upstream_timeout: Duration,
+        max_retries: u8,
+        verbose: bool,
+    ) -> Self {
+        let socket = socket.try_clone().expect("Socket should be clonable.");
+        let pending_queries = pending_queries.clone();
+        let cache = cache.clone();
+        let zones = zones.clone();
+        let handler = handler.clone();
+        let metrics = metrics.clone();
+        let (stop_signal_sender, stop_signal_receiver) = channel();
+
+        let handle = thread::spawn(move || {
+            Self::run(
+                socket,
+                upstream,
+                upstream_replies,
+                pending_queries,
+                cache,
+                zones,
+                id_range,
+                handler,
+                metrics,
+                upstream_timeout,
+                max_retries,
+                verbose,
+                stop_signal_receiver,
+            );
+        });
+
+        Self {
+            handle: Some(handle),
+            stop_signal_sender,
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn run(
+        socket: UdpSocket,
+        upstream: Arc<dyn UpstreamResolver>,
+        upstream_replies: Receiver<Vec<u8>>,
+        pending_queries: ThreadSafeStore,
+        cache: Option<ThreadSafeCache>,
+        zones: ZoneStore,
+        id_range: Range<u16>,
+        handler: HandlerHolder,
+        metrics: Metrics,
+        upstream_timeout: Duration,
+        max_retries: u8,
+        verbose: bool,
+        stop_signal: Receiver<()>,
+    ) {
+        let mut next_id = id_range.start;
+        let mut buffer = [0u8; 4096];
+
+        loop {
+            if stop_signal.try_recv().is_ok() {
+                break;
+            }
+
+            while let Ok(reply) = upstream_replies.try_recv() {
+                Self::on_upstream_reply(&socket, &pending_queries, &cache, &reply);
+            }
+
+            let (size, from) = match socket.recv_from(&mut buffer) {
+                Ok(result) => result,
+                Err(e)
+                    if e.kind() == std::io::ErrorKind::WouldBlock
+                        || e.kind() == std::io::ErrorKind::TimedOut =>
+                {
+                    Self::sweep_pending_queries(
+                        &socket,
+                        upstream.as_ref(),
+                        &pending_queries,
+                        &metrics,
+                        upstream_timeout,
+                        max_retries,
+                    );
+                    continue;
+                }
+                Err(e) => {
+                    if verbose {
+                        eprintln!("any-dns: failed to read from socket: {e}");
+                    }
+                    continue;
+                }
+            };
+
+            let packet = &buffer[..size];
+
+            Self::on_client_query(
+                &socket,
+                upstream.as_ref(),
+                &pending_queries,
+                &cache,
+                &zones,
+                &id_range,
+                &mut next_id,
+                &handler,
+                &metrics,
+                packet,
+                from,
+                verbose,
+            );
+        }
+    }
+
+    /// Handles a query coming in from a client: try the local zones first, then
+    /// the custom handler, then the cache, and fall back to forwarding it upstream.
+    #[allow(clippy::too_many_arguments)]
+    fn on_client_query(
+        socket: &UdpSocket,
+        upstream: &dyn UpstreamResolver,
+        pending_queries: &ThreadSafeStore,
+        cache: &Option<ThreadSafeCache>,
+        zones: &ZoneStore,
+        id_range: &Range<u16>,
+        next_id: &mut u16,
+        handler: &HandlerHolder,
+        metrics: &Metrics,
+        query: &[u8],
+        from: SocketAddr,
+        verbose: bool,
+    ) {
+        metrics.record_query_received();
+
+        if let Some(reply) = zone_reply(zones, query) {
+            let _ = socket.send_to(&reply, from);
+            return;
+        }
+
+        match handler.handle(query) {
+            Ok(reply) => {
+                metrics.record_handler_hit();
+                let _ = socket.send_to(&reply, from);
+                return;
+            }
+            Err(_) => metrics.record_handler_error(),
+        }
+
+        if cache.is_some() {
+            match cached_reply(cache, query) {
+                Some(reply) => {
+                    metrics.record_cache_hit();
+                    let _ = socket.send_to(&reply, from);
+                    return;
+                }
+                None => metrics.record_cache_miss(),
+            }
+        }
+
+        let id = Self::next_id(next_id, id_range);
+        pending_queries.insert(id, PendingQuery::new(from, query.to_vec()));
+
+        let mut forwarded = query.to_vec();
+        let id_bytes = id.to_be_bytes();
+        forwarded[0] = id_bytes[0];
+        forwarded[1] = id_bytes[1];
+
+        if let Err(e) = upstream.send(&forwarded) {
+            if verbose {
+                eprintln!("any-dns: failed to forward query upstream: {e}");
+            }
+            pending_queries.remove(&id);
+        } else {
+            metrics.record_upstream_forward();
+        }
+    }
+
+    /// Handles a reply coming back from the upstream resolver, matching it to
+    /// the pending query it answers and relaying it to the original client.
+    fn on_upstream_reply(
+        socket: &UdpSocket,
+        pending_queries: &ThreadSafeStore,
+        cache: &Option<ThreadSafeCache>,
+        packet: &[u8],
+    ) {
+        let reply = match Packet::parse(packet) {
+            Ok(reply) => reply,
+            Err(_) => return,
+        };
+
+        let Some(pending) = pending_queries.remove(&reply.id()) else {
+            return;
+        };
+
+        cache_reply(cache, &pending.query, &reply);
+
+        let _ = socket.send_to(&with_original_id(packet, &pending.query), pending.from);
+    }
+
+    /// Retransmits pending queries whose retransmit delay has elapsed, and gives up
+    /// (replying SERVFAIL to the original client) on queries that exhausted their
+    /// retries or blew past the total upstream deadline.
+    fn sweep_pending_queries(
+        socket: &UdpSocket,
+        upstream: &dyn UpstreamResolver,
+        pending_queries: &ThreadSafeStore,
+        metrics: &Metrics,
+        upstream_timeout: Duration,
+        max_retries: u8,
+    ) {
+        let now = Instant::now();
+
+        let expired = pending_queries.drain_matching(|pending| {
+            pending.retries >= max_retries || now.duration_since(pending.created_at) >= upstream_timeout
+        });
+        for (_, pending) in expired {
+            metrics.record_upstream_timeout();
+            let _ = socket.send_to(&servfail_reply(&pending.query), pending.from);
+        }
+
+        let due = pending_queries
+            .ids_matching(|pending| now.duration_since(pending.sent_at) >= Self::retransmit_delay(pending.retries));
+        for id in due {
+            let Some(pending) = pending_queries.get(&id) else {
+                continue;
+            };
+
+            let mut forwarded = pending.query.clone();
+            let id_bytes = id.to_be_bytes();
+            forwarded[0] = id_bytes[0];
+            forwarded[1] = id_bytes[1];
+
+            if upstream.send(&forwarded).is_ok() {
+                pending_queries.update(&id, |pending| {
+                    pending.sent_at = Instant::now();
+                    pending.retries += 1;
+                });
+            }
+        }
+    }
+
+    /// Retransmit backoff: 1s, 2s, 4s, 8s, capped at 10s.
+    fn retransmit_delay(retries: u8) -> Duration {
+        INITIAL_RETRANSMIT_DELAY
+            .checked_mul(1 << retries.min(6))
+            .unwrap_or(MAX_RETRANSMIT_DELAY)
+            .min(MAX_RETRANSMIT_DELAY)
+    }
+
+    fn next_id(next_id: &mut u16, range: &Range<u16>) -> u16 {
+        let id = *next_id;
+        *next_id = if *next_id + 1 >= range.end {
+            range.start
+        } else {
+            *next_id + 1
+        };
+        id
+    }
+
+    pub fn stop(&mut self) {
+        let _ = self.stop_signal_sender.send(());
+    }
+
+    pub fn join(mut self) {
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl fmt::Debug for DnsThread {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DnsThread").finish()
+    }
+}
+
+/// Builds an authoritative reply for `query` straight from a local zone, if its
+/// question falls under one of the configured zone suffixes. Shared with
+/// [`crate::tcp::TcpDnsListener`] so TCP clients see the same zones.
+pub(crate) fn zone_reply(zones: &ZoneStore, query: &[u8]) -> Option<Vec<u8>> {
+    let parsed = Packet::parse(query).ok()?;
+    let question = parsed.questions.first()?;
+    let answer = zones.answer(question)?;
+
+    let mut reply = Packet::new_reply(parsed.id());
+    reply.set_flags(PacketFlag::AUTHORITATIVE_ANSWER);
+    reply.questions.push(question.clone());
+
+    match answer {
+        ZoneAnswer::Found(answers) => {
+            reply.answers.extend(answers);
+        }
+        ZoneAnswer::NoData { soa } => {
+            reply.name_servers.push(soa);
+        }
+        ZoneAnswer::NxDomain { soa } => {
+            *reply.rcode_mut() = RCODE::NameError;
+            reply.name_servers.push(soa);
+        }
+    }
+
+    reply.build_bytes_vec().ok()
+}
+
+/// Builds a reply straight from the cache for `query`, if its question has an
+/// unexpired entry. Shared with [`crate::tcp::TcpDnsListener`].
+pub(crate) fn cached_reply(cache: &Option<ThreadSafeCache>, query: &[u8]) -> Option<Vec<u8>> {
+    let cache = cache.as_ref()?;
+    let parsed = Packet::parse(query).ok()?;
+    let question = parsed.questions.first()?;
+    let answers = cache.get(question)?;
+
+    let mut reply = Packet::new_reply(parsed.id());
+    reply.questions.push(question.clone());
+    reply.answers.extend(answers);
+    reply.build_bytes_vec().ok()
+}
+
+/// Stores `reply`'s answers for the question in the client's original query, if
+/// caching is enabled. Shared with [`crate::tcp::TcpDnsListener`].
+pub(crate) fn cache_reply(cache: &Option<ThreadSafeCache>, original_query: &[u8], reply: &Packet) {
+    let Some(cache) = cache.as_ref() else {
+        return;
+    };
+    let Ok(original_query) = Packet::parse(original_query) else {
+        return;
+    };
+    let Some(question) = original_query.questions.first() else {
+        return;
+    };
+
+    let answers: Vec<_> = reply
+        .answers
+        .iter()
+        .cloned()
+        .map(|answer| answer.into_owned())
+        .collect();
+    cache.put(question, reply.rcode(), &answers);
+}
+
+/// Rewrites the id in `reply` to match the id of the client's `original_query`,
+/// since each thread reassigns ids from its own bucket before forwarding upstream.
+/// Shared with [`crate::tcp::TcpDnsListener`].
+pub(crate) fn with_original_id(reply: &[u8], original_query: &[u8]) -> Vec<u8> {
+    let mut reply = reply.to_vec();
+    if reply.len() >= 2 && original_query.len() >= 2 {
+        reply[0] = original_query[0];
+        reply[1] = original_query[1];
+    }
+    reply
+}
+
+/// Builds a SERVFAIL reply for the client that sent `original_query`, echoing
+/// its id and questions so the client can match it to its request. Shared with
+/// [`crate::tcp::TcpDnsListener`].
+pub(crate) fn servfail_reply(original_query: &[u8]) -> Vec<u8> {
+    let id = original_query
+        .get(0..2)
+        .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+        .unwrap_or_default();
+
+    let mut reply = Packet::new_reply(id);
+    if let Ok(parsed) = Packet::parse(original_query) {
+        reply.questions = parsed.questions;
+    }
+    *reply.rcode_mut() = RCODE::ServerFailure;
+
+    reply.build_bytes_vec().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retransmit_delay_doubles_each_retry_up_to_the_cap() {
+        assert_eq!(DnsThread::retransmit_delay(0), Duration::from_secs(1));
+        assert_eq!(DnsThread::retransmit_delay(1), Duration::from_secs(2));
+        assert_eq!(DnsThread::retransmit_delay(2), Duration::from_secs(4));
+        assert_eq!(DnsThread::retransmit_delay(3), Duration::from_secs(8));
+        assert_eq!(DnsThread::retransmit_delay(4), MAX_RETRANSMIT_DELAY);
+        assert_eq!(DnsThread::retransmit_delay(255), MAX_RETRANSMIT_DELAY);
+    }
+
+    #[test]
+    fn next_id_wraps_around_within_its_range() {
+        let range = 10..13;
+        let mut next = range.start;
+
+        assert_eq!(DnsThread::next_id(&mut next, &range), 10);
+        assert_eq!(DnsThread::next_id(&mut next, &range), 11);
+        assert_eq!(DnsThread::next_id(&mut next, &range), 12);
+        assert_eq!(DnsThread::next_id(&mut next, &range), 10);
+    }
+}
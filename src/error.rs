@@ -0,0 +1,38 @@
+//! Error types returned by this crate.
+
+use std::fmt;
+
+/// Convenience alias for results returned by this crate.
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Errors that can occur while building or running [`crate::AnyDNS`].
+#[derive(Debug)]
+pub enum Error {
+    /// An I/O operation (binding a socket, reading/writing) failed.
+    Io(std::io::Error),
+    /// A DNS packet could not be parsed or built.
+    Dns(simple_dns::SimpleDnsError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "IO error: {e}"),
+            Error::Dns(e) => write!(f, "DNS packet error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<simple_dns::SimpleDnsError> for Error {
+    fn from(e: simple_dns::SimpleDnsError) -> Self {
+        Error::Dns(e)
+    }
+}
@@ -1,8 +1,19 @@
 #![allow(unused)]
 
+pub mod cache;
+pub mod custom_handler;
+pub mod dns_thread;
 pub mod error;
+mod metrics;
+pub mod pending_queries;
 pub mod server;
-mod task_queue;
+mod tcp;
+pub mod upstream;
+pub mod zones;
 
+pub use crate::custom_handler::{CustomHandler, CustomHandlerError, CustomHandlerResult};
 pub use crate::error::{Error, Result};
-pub use crate::server::{AnyDNS, Builder};
\ No newline at end of file
+pub use crate::metrics::Stats;
+pub use crate::server::{AnyDNS, Builder};
+pub use crate::upstream::Upstream;
+pub use crate::zones::Zone;
\ No newline at end of file
@@ -67,7 +67,7 @@ impl AnyDNS {
                 query[0] = id_bytes[0];
                 query[1] = id_bytes[1];
 
-                socket.send_to(&query, self.icann_resolver).unwrap();
+                socket.send_to(query, self.icann_resolver).unwrap();
             }
         }
     }
@@ -0,0 +1,199 @@
+//! Per-thread counters aggregated behind an [`Arc`] and exposed as a snapshot via
+//! [`crate::server::AnyDNS::stats`], plus an optional Prometheus-style text endpoint.
+
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{channel, Receiver, Sender},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+/// Shared counters incremented by every [`crate::dns_thread::DnsThread`] as it
+/// processes traffic. Cheap to clone: every clone shares the same underlying counters.
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    inner: Arc<Counters>,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    queries_received: AtomicU64,
+    handler_hits: AtomicU64,
+    handler_errors: AtomicU64,
+    upstream_forwards: AtomicU64,
+    upstream_timeouts: AtomicU64,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record_query_received(&self) {
+        self.inner.queries_received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_handler_hit(&self) {
+        self.inner.handler_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_handler_error(&self) {
+        self.inner.handler_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_upstream_forward(&self) {
+        self.inner.upstream_forwards.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_upstream_timeout(&self) {
+        self.inner.upstream_timeouts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_cache_hit(&self) {
+        self.inner.cache_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_cache_miss(&self) {
+        self.inner.cache_misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Snapshots the current counters.
+    pub fn snapshot(&self) -> Stats {
+        Stats {
+            queries_received: self.inner.queries_received.load(Ordering::Relaxed),
+            handler_hits: self.inner.handler_hits.load(Ordering::Relaxed),
+            handler_errors: self.inner.handler_errors.load(Ordering::Relaxed),
+            upstream_forwards: self.inner.upstream_forwards.load(Ordering::Relaxed),
+            upstream_timeouts: self.inner.upstream_timeouts.load(Ordering::Relaxed),
+            cache_hits: self.inner.cache_hits.load(Ordering::Relaxed),
+            cache_misses: self.inner.cache_misses.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Renders the current counters as Prometheus text exposition format.
+    fn render_prometheus(&self) -> String {
+        let stats = self.snapshot();
+        format!(
+            "# TYPE any_dns_queries_received_total counter\n\
+             any_dns_queries_received_total {}\n\
+             # TYPE any_dns_handler_hits_total counter\n\
+             any_dns_handler_hits_total {}\n\
+             # TYPE any_dns_handler_errors_total counter\n\
+             any_dns_handler_errors_total {}\n\
+             # TYPE any_dns_upstream_forwards_total counter\n\
+             any_dns_upstream_forwards_total {}\n\
+             # TYPE any_dns_upstream_timeouts_total counter\n\
+             any_dns_upstream_timeouts_total {}\n\
+             # TYPE any_dns_cache_hits_total counter\n\
+             any_dns_cache_hits_total {}\n\
+             # TYPE any_dns_cache_misses_total counter\n\
+             any_dns_cache_misses_total {}\n",
+            stats.queries_received,
+            stats.handler_hits,
+            stats.handler_errors,
+            stats.upstream_forwards,
+            stats.upstream_timeouts,
+            stats.cache_hits,
+            stats.cache_misses,
+        )
+    }
+}
+
+/// A point-in-time snapshot of [`Metrics`]' counters, returned by [`crate::server::AnyDNS::stats`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub queries_received: u64,
+    pub handler_hits: u64,
+    pub handler_errors: u64,
+    pub upstream_forwards: u64,
+    pub upstream_timeouts: u64,
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+}
+
+/// Serves `metrics`, rendered as Prometheus text, to any TCP client that connects.
+/// Used to back [`crate::Builder::metrics_listen`].
+pub(crate) struct MetricsServer {
+    handle: Option<JoinHandle<()>>,
+    stop_signal_sender: Sender<()>,
+}
+
+impl MetricsServer {
+    pub(crate) fn new(listen: SocketAddr, metrics: Metrics) -> io::Result<Self> {
+        let listener = TcpListener::bind(listen)?;
+        listener.set_nonblocking(true)?;
+        let (stop_signal_sender, stop_signal_receiver) = channel();
+
+        let handle = thread::spawn(move || {
+            Self::run(listener, metrics, stop_signal_receiver);
+        });
+
+        Ok(Self {
+            handle: Some(handle),
+            stop_signal_sender,
+        })
+    }
+
+    fn run(listener: TcpListener, metrics: Metrics, stop_signal: Receiver<()>) {
+        loop {
+            if stop_signal.try_recv().is_ok() {
+                break;
+            }
+
+            match listener.accept() {
+                Ok((stream, _from)) => {
+                    let metrics = metrics.clone();
+                    thread::spawn(move || {
+                        let _ = Self::serve(stream, &metrics);
+                    });
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    thread::sleep(Duration::from_millis(100));
+                }
+                Err(_e) => thread::sleep(Duration::from_millis(100)),
+            }
+        }
+    }
+
+    /// Responds to a single scrape, ignoring the request's method and path.
+    fn serve(mut stream: TcpStream, metrics: &Metrics) -> io::Result<()> {
+        // A client that connects but never sends anything (or stalls mid-request)
+        // would otherwise park this thread forever.
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+
+        let mut discard = [0u8; 1024];
+        let _ = stream.read(&mut discard);
+
+        let body = metrics.render_prometheus();
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body,
+        );
+        stream.write_all(response.as_bytes())
+    }
+
+    pub(crate) fn stop(&mut self) {
+        let _ = self.stop_signal_sender.send(());
+    }
+
+    pub(crate) fn join(mut self) {
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl fmt::Debug for MetricsServer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MetricsServer").finish()
+    }
+}
@@ -0,0 +1,110 @@
+//! Tracks queries that have been forwarded upstream and are waiting for a reply,
+//! so the reply can be matched back to the client that asked for it.
+
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+/// A single query that was forwarded to `icann_resolver` and hasn't been answered yet.
+#[derive(Debug, Clone)]
+pub struct PendingQuery {
+    /// Address of the client that originally sent the query.
+    pub from: SocketAddr,
+    /// Raw bytes of the original query, as received from the client.
+    pub query: Vec<u8>,
+    /// When the query was first forwarded upstream. Used to enforce the total deadline.
+    pub created_at: Instant,
+    /// When the query was last (re)sent to the upstream resolver. Used to schedule the next retransmit.
+    pub sent_at: Instant,
+    /// How many times the query has been retransmitted upstream.
+    pub retries: u8,
+}
+
+impl PendingQuery {
+    pub fn new(from: SocketAddr, query: Vec<u8>) -> Self {
+        let now = Instant::now();
+        Self {
+            from,
+            query,
+            created_at: now,
+            sent_at: now,
+            retries: 0,
+        }
+    }
+}
+
+/// `HashMap<u16, PendingQuery>` wrapped so it can be shared and mutated by every
+/// [`crate::dns_thread::DnsThread`] without each one owning its own copy.
+#[derive(Debug, Clone)]
+pub struct ThreadSafeStore {
+    inner: Arc<Mutex<HashMap<u16, PendingQuery>>>,
+}
+
+impl ThreadSafeStore {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn insert(&self, id: u16, query: PendingQuery) {
+        self.inner.lock().unwrap().insert(id, query);
+    }
+
+    pub fn remove(&self, id: &u16) -> Option<PendingQuery> {
+        self.inner.lock().unwrap().remove(id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.lock().unwrap().is_empty()
+    }
+
+    /// Returns the ids of all entries for which `f` returns `true`, without removing them.
+    pub fn ids_matching(&self, f: impl Fn(&PendingQuery) -> bool) -> Vec<u16> {
+        self.inner
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, query)| f(query))
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    /// Removes and returns every entry for which `f` returns `true`.
+    pub fn drain_matching(&self, f: impl Fn(&PendingQuery) -> bool) -> Vec<(u16, PendingQuery)> {
+        let mut inner = self.inner.lock().unwrap();
+        let ids: Vec<u16> = inner
+            .iter()
+            .filter(|(_, query)| f(query))
+            .map(|(id, _)| *id)
+            .collect();
+        ids.into_iter()
+            .filter_map(|id| inner.remove(&id).map(|query| (id, query)))
+            .collect()
+    }
+
+    /// Returns a clone of the entry for `id`, if it is still pending.
+    pub fn get(&self, id: &u16) -> Option<PendingQuery> {
+        self.inner.lock().unwrap().get(id).cloned()
+    }
+
+    /// Applies `f` to the entry for `id`, if it is still pending.
+    pub fn update(&self, id: &u16, f: impl FnOnce(&mut PendingQuery)) {
+        if let Some(query) = self.inner.lock().unwrap().get_mut(id) {
+            f(query);
+        }
+    }
+}
+
+impl Default for ThreadSafeStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
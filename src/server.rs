@@ -3,18 +3,32 @@
 use simple_dns::{Packet, Name, Question};
 use std::{
     collections::HashMap,
-    net::{SocketAddr, UdpSocket}, str::FromStr, thread::sleep, time::{Duration, Instant}, sync::{mpsc::channel, Arc, Mutex}, ops::Range,
+    net::{SocketAddr, UdpSocket}, str::FromStr, thread::{self, sleep}, time::{Duration, Instant}, sync::{mpsc::{channel, Sender}, Arc, Mutex}, ops::Range,
 };
 
-use crate::{dns_thread::DnsThread, pending_queries::{self, PendingQuery, ThreadSafeStore}, custom_handler::{HandlerHolder, EmptyHandler, CustomHandler}};
+use crate::{dns_thread::DnsThread, pending_queries::{self, PendingQuery, ThreadSafeStore}, custom_handler::{HandlerHolder, EmptyHandler, CustomHandler}, cache::ThreadSafeCache, metrics::{Metrics, MetricsServer, Stats}, tcp::TcpDnsListener, upstream::{Upstream, UpstreamResolver}, zones::{Zone, ZoneStore}};
+
+/// Default number of questions kept in the response cache. See [`Builder::cache_size`].
+const DEFAULT_CACHE_SIZE: usize = 10_000;
+/// Default total deadline for an upstream query. See [`Builder::upstream_timeout`].
+const DEFAULT_UPSTREAM_TIMEOUT: Duration = Duration::from_secs(10);
+/// Default retry cap for an upstream query. See [`Builder::max_retries`].
+const DEFAULT_MAX_RETRIES: u8 = 3;
 
 
 
 pub struct Builder {
     icann_resolver: SocketAddr,
+    icann_resolvers: Option<(Vec<SocketAddr>, Vec<SocketAddr>)>,
+    upstream: Option<Upstream>,
     listen: SocketAddr,
     thread_count: u8,
     handler: HandlerHolder,
+    cache_size: usize,
+    upstream_timeout: Duration,
+    max_retries: u8,
+    zones: Vec<Zone>,
+    metrics_listen: Option<SocketAddr>,
     verbose: bool
 }
 
@@ -22,19 +36,46 @@ impl Builder {
     pub fn new() -> Self {
         Self {
             icann_resolver: SocketAddr::from(([192, 168, 1, 1], 53)),
+            icann_resolvers: None,
+            upstream: None,
             listen: SocketAddr::from(([0, 0, 0, 0], 53)),
             thread_count: 1,
             handler: HandlerHolder::new(EmptyHandler::new()),
+            cache_size: DEFAULT_CACHE_SIZE,
+            upstream_timeout: DEFAULT_UPSTREAM_TIMEOUT,
+            max_retries: DEFAULT_MAX_RETRIES,
+            zones: Vec::new(),
+            metrics_listen: None,
             verbose: false
         }
     }
 
-    /// Set the DNS resolver for normal ICANN domains. Defaults to 192.168.1.1:53
+    /// Set the DNS resolver for normal ICANN domains. Defaults to 192.168.1.1:53.
+    /// Used as the plain-UDP transport unless [`Builder::upstream`] or
+    /// [`Builder::icann_resolvers`] overrides it. The TCP/53 listener forwards through
+    /// the same upstream transport as the UDP threads.
     pub fn icann_resolver(mut self, icann_resolver: SocketAddr) -> Self {
         self.icann_resolver = icann_resolver;
         self
     }
 
+    /// Send queries upstream over an encrypted transport (DoH or DoT) instead of
+    /// plain UDP to `icann_resolver`. Defaults to plain UDP.
+    pub fn upstream(mut self, upstream: Upstream) -> Self {
+        self.upstream = Some(upstream);
+        self
+    }
+
+    /// Spread queries over several plain-UDP ICANN resolvers instead of a single one:
+    /// `preferred` is tried first (round-robin), falling over to `fallback` once every
+    /// preferred resolver has been tried for a given query. A resolver that keeps timing
+    /// out is temporarily skipped. Overrides [`Builder::icann_resolver`] as the upstream
+    /// transport, unless [`Builder::upstream`] is also set (which takes priority).
+    pub fn icann_resolvers(mut self, preferred: Vec<SocketAddr>, fallback: Vec<SocketAddr>) -> Self {
+        self.icann_resolvers = Some((preferred, fallback));
+        self
+    }
+
     /// Set socket the server should listen on. Defaults to 0.0.0.0:53
     pub fn listen(mut self, listen: SocketAddr) -> Self {
         self.listen = listen;
@@ -59,26 +100,140 @@ impl Builder {
         self
     }
 
+    /// Set how many questions the response cache keeps. `0` disables caching entirely.
+    /// Defaults to 10 000.
+    pub fn cache_size(mut self, cache_size: usize) -> Self {
+        self.cache_size = cache_size;
+        self
+    }
+
+    /// Set the total deadline for a query forwarded to `icann_resolver` before giving up
+    /// and replying SERVFAIL. Defaults to 10 seconds.
+    pub fn upstream_timeout(mut self, upstream_timeout: Duration) -> Self {
+        self.upstream_timeout = upstream_timeout;
+        self
+    }
+
+    /// Set how many times a query forwarded to `icann_resolver` is retransmitted before
+    /// giving up and replying SERVFAIL. Defaults to 3. With [`Builder::icann_resolvers`]
+    /// (and no [`Builder::upstream`]), it is raised to at least one less than the number
+    /// of resolvers in the pool.
+    pub fn max_retries(mut self, max_retries: u8) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Register a locally-authoritative zone. Questions falling under its suffix are
+    /// answered directly, without touching ICANN or the custom handler.
+    pub fn zone(mut self, zone: Zone) -> Self {
+        self.zones.push(zone);
+        self
+    }
+
+    /// Serve a Prometheus-style text exposition of [`AnyDNS::stats`] on `listen`.
+    /// Disabled by default.
+    pub fn metrics_listen(mut self, listen: SocketAddr) -> Self {
+        self.metrics_listen = Some(listen);
+        self
+    }
+
     /** Build and start server. */
     pub fn build(self) -> AnyDNS {
         let socket = UdpSocket::bind(self.listen).expect("Listen address should be available");
         socket.set_read_timeout(Some(Duration::from_millis(500))); // So the DNS can be stopped.
         let pending_queries = ThreadSafeStore::new();
+        let cache = ThreadSafeCache::new(self.cache_size);
+        let zones = ZoneStore::new(self.zones);
+        let upstream = self.upstream.clone().unwrap_or_else(|| match self.icann_resolvers.clone() {
+            Some((preferred, fallback)) => Upstream::UdpPool { preferred, fallback },
+            None => Upstream::Udp(self.icann_resolver),
+        });
+        // A pool needs at least one retry per resolver to ever reach the fallback
+        // set, so `max_retries` must grow with the pool instead of being capped
+        // independently of how many resolvers are configured.
+        let max_retries = match &upstream {
+            Upstream::UdpPool { preferred, fallback } => {
+                let resolver_count = (preferred.len() + fallback.len()) as u8;
+                self.max_retries.max(resolver_count.saturating_sub(1))
+            }
+            _ => self.max_retries,
+        };
+        let metrics = Metrics::new();
+
+        // One `UpstreamResolver` (and so one underlying socket/connection) for the
+        // whole server, shared by every `DnsThread` and the TCP listener, so a dead
+        // upstream or a DoT connection drop affects all traffic identically instead
+        // of each consumer maintaining its own independent transport. Replies for
+        // every consumer arrive interleaved on `shared_replies`; a demux thread
+        // below splits them back out by which id range they fall into.
+        let (shared_replies_tx, shared_replies_rx) = channel();
+        let upstream: Arc<dyn UpstreamResolver> = Arc::from(
+            upstream
+                .build(shared_replies_tx, self.upstream_timeout)
+                .expect("Upstream resolver should be constructible."),
+        );
+
+        // The TCP listener gets its own reserved bucket, one past the UDP threads'.
+        let total_buckets = self.thread_count as u16 + 1;
+        let mut reply_routes: Vec<(Range<u16>, Sender<Vec<u8>>)> = Vec::new();
+
         let mut threads = vec![];
         for i in 0..self.thread_count {
-            let id_range = Self::calculate_id_range(self.thread_count as u16, i as u16);
-            let thread = DnsThread::new(&socket, &self.icann_resolver, &pending_queries, id_range, &self.handler, self.verbose);
+            let id_range = Self::calculate_id_range(total_buckets, i as u16);
+            let (replies_tx, replies_rx) = channel();
+            reply_routes.push((id_range.clone(), replies_tx));
+
+            let thread = DnsThread::new(&socket, upstream.clone(), replies_rx, &pending_queries, &cache, &zones, id_range, &self.handler, &metrics, self.upstream_timeout, max_retries, self.verbose);
             threads.push(thread);
         }
 
+        let tcp_id_range = Self::calculate_id_range(total_buckets, self.thread_count as u16);
+        let (tcp_replies_tx, tcp_replies_rx) = channel();
+        reply_routes.push((tcp_id_range.clone(), tcp_replies_tx));
+
+        thread::spawn(move || {
+            while let Ok(reply) = shared_replies_rx.recv() {
+                let Ok(parsed) = Packet::parse(&reply) else {
+                    continue;
+                };
+                let id = parsed.id();
+                if let Some((_, sender)) = reply_routes.iter().find(|(range, _)| range.contains(&id)) {
+                    let _ = sender.send(reply);
+                }
+            }
+        });
+
+        // A single TCP acceptor serves clients that retry over TCP/53 (e.g. after
+        // receiving a truncated UDP reply) and large responses that wouldn't fit in a UDP datagram.
+        // It shares the same upstream/zones/cache/metrics plumbing as the UDP threads.
+        let tcp_listener = TcpDnsListener::new(
+            self.listen,
+            upstream,
+            tcp_replies_rx,
+            tcp_id_range,
+            &zones,
+            &cache,
+            &self.handler,
+            &metrics,
+            self.upstream_timeout,
+        )
+        .expect("TCP listen address should be available");
+
+        let metrics_server = self.metrics_listen.map(|listen| {
+            MetricsServer::new(listen, metrics.clone()).expect("Metrics listen address should be available")
+        });
+
         AnyDNS {
-            threads
+            threads,
+            tcp_listener,
+            metrics_server,
+            metrics,
         }
     }
 
-    /** Calculates the dns packet id range for each thread. */
-    fn calculate_id_range(thread_count: u16, i: u16) -> Range<u16> {
-        let bucket_size = u16::MAX / thread_count;
+    /** Calculates the dns packet id range for each bucket (one per `DnsThread`, plus one reserved for the TCP listener). */
+    fn calculate_id_range(bucket_count: u16, i: u16) -> Range<u16> {
+        let bucket_size = u16::MAX / bucket_count;
         Range{
             start: i * bucket_size,
             end: (i + 1) * bucket_size -1
@@ -86,9 +241,18 @@ impl Builder {
     }
 }
 
+impl Default for Builder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[derive(Debug)]
 pub struct AnyDNS {
     threads: Vec<DnsThread>,
+    tcp_listener: TcpDnsListener,
+    metrics_server: Option<MetricsServer>,
+    metrics: Metrics,
 }
 
 impl AnyDNS {
@@ -99,9 +263,17 @@ impl AnyDNS {
         for thread in self.threads.iter_mut() {
             thread.stop();
         };
+        self.tcp_listener.stop();
+        if let Some(metrics_server) = self.metrics_server.as_mut() {
+            metrics_server.stop();
+        }
         for thread in self.threads {
             thread.join()
         };
+        self.tcp_listener.join();
+        if let Some(metrics_server) = self.metrics_server {
+            metrics_server.join();
+        }
     }
 
     /**
@@ -113,6 +285,11 @@ impl AnyDNS {
             .expect("Error setting Ctrl-C handler");
         rx.recv().expect("Could not receive from channel.");
     }
+
+    /// Snapshots the server's traffic counters. See [`Stats`].
+    pub fn stats(&self) -> Stats {
+        self.metrics.snapshot()
+    }
 }
 
 impl Default for AnyDNS {
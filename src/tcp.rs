@@ -0,0 +1,301 @@
+//! TCP support: a listener that serves clients over TCP/53, and a helper to
+//! re-issue a query to an upstream resolver over TCP when a UDP answer came back truncated.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    ops::Range,
+    sync::{
+        atomic::{AtomicU16, Ordering},
+        mpsc::{channel, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use simple_dns::Packet;
+
+use crate::{
+    cache::ThreadSafeCache,
+    custom_handler::HandlerHolder,
+    dns_thread::{cache_reply, cached_reply, servfail_reply, with_original_id, zone_reply},
+    metrics::Metrics,
+    upstream::UpstreamResolver,
+    zones::ZoneStore,
+};
+
+/// Sends `query` to `resolver` over a fresh TCP connection and returns the reply.
+/// DNS-over-TCP messages are prefixed with a 2-byte big-endian length.
+pub fn send_tcp_query(resolver: SocketAddr, query: &[u8]) -> io::Result<Vec<u8>> {
+    let mut stream = TcpStream::connect(resolver)?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+
+    let len = (query.len() as u16).to_be_bytes();
+    stream.write_all(&len)?;
+    stream.write_all(query)?;
+
+    read_tcp_message(&mut stream)
+}
+
+/// Reads a single 2-byte-length-prefixed DNS message off `stream`.
+fn read_tcp_message(stream: &mut TcpStream) -> io::Result<Vec<u8>> {
+    let mut len_buf = [0u8; 2];
+    stream.read_exact(&mut len_buf)?;
+    let len = u16::from_be_bytes(len_buf) as usize;
+
+    let mut buf = vec![0u8; len];
+    stream.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+/// Replies forwarded upstream are matched back to the connection waiting on them
+/// by DNS id, same as [`crate::dns_thread::DnsThread`] does for UDP clients.
+type PendingReplies = Arc<Mutex<HashMap<u16, Sender<Vec<u8>>>>>;
+
+/// Accepts DNS-over-TCP connections on `listen` and answers them through the same
+/// zone/handler/cache/upstream path used by the UDP [`crate::dns_thread::DnsThread`]s,
+/// so configuring [`crate::Builder::upstream`], [`crate::Builder::zone`] or the
+/// response cache also takes effect for TCP clients.
+pub struct TcpDnsListener {
+    handle: Option<JoinHandle<()>>,
+    stop_signal_sender: Sender<()>,
+}
+
+impl TcpDnsListener {
+    /// `upstream` is shared with every [`crate::dns_thread::DnsThread`] (one
+    /// resolver, one underlying connection, for the whole server); `upstream_replies`
+    /// delivers only the replies for this listener's slice of the id space, already
+    /// demultiplexed by the caller. `id_range` bounds the ids this listener assigns
+    /// to queries it forwards upstream.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        listen: SocketAddr,
+        upstream: Arc<dyn UpstreamResolver>,
+        upstream_replies: Receiver<Vec<u8>>,
+        id_range: Range<u16>,
+        zones: &ZoneStore,
+        cache: &Option<ThreadSafeCache>,
+        handler: &HandlerHolder,
+        metrics: &Metrics,
+        upstream_timeout: Duration,
+    ) -> io::Result<Self> {
+        let listener = TcpListener::bind(listen)?;
+        listener.set_nonblocking(true)?;
+
+        let pending: PendingReplies = Arc::new(Mutex::new(HashMap::new()));
+
+        let demux_pending = pending.clone();
+        thread::spawn(move || {
+            while let Ok(reply) = upstream_replies.recv() {
+                let Ok(parsed) = Packet::parse(&reply) else {
+                    continue;
+                };
+                if let Some(sender) = demux_pending.lock().unwrap().remove(&parsed.id()) {
+                    let _ = sender.send(reply);
+                }
+            }
+        });
+
+        let zones = zones.clone();
+        let cache = cache.clone();
+        let handler = handler.clone();
+        let metrics = metrics.clone();
+        let next_id = Arc::new(AtomicU16::new(id_range.start));
+        let (stop_signal_sender, stop_signal_receiver) = channel();
+
+        let handle = thread::spawn(move || {
+            Self::run(
+                listener,
+                upstream,
+                pending,
+                next_id,
+                id_range,
+                zones,
+                cache,
+                handler,
+                metrics,
+                upstream_timeout,
+                stop_signal_receiver,
+            );
+        });
+
+        Ok(Self {
+            handle: Some(handle),
+            stop_signal_sender,
+        })
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn run(
+        listener: TcpListener,
+        upstream: Arc<dyn UpstreamResolver>,
+        pending: PendingReplies,
+        next_id: Arc<AtomicU16>,
+        id_range: Range<u16>,
+        zones: ZoneStore,
+        cache: Option<ThreadSafeCache>,
+        handler: HandlerHolder,
+        metrics: Metrics,
+        upstream_timeout: Duration,
+        stop_signal: Receiver<()>,
+    ) {
+        loop {
+            if stop_signal.try_recv().is_ok() {
+                break;
+            }
+
+            match listener.accept() {
+                Ok((stream, _from)) => {
+                    let upstream = upstream.clone();
+                    let pending = pending.clone();
+                    let next_id = next_id.clone();
+                    let zones = zones.clone();
+                    let cache = cache.clone();
+                    let handler = handler.clone();
+                    let metrics = metrics.clone();
+
+                    let id_range = id_range.clone();
+                    thread::spawn(move || {
+                        if let Err(_e) = Self::serve(
+                            stream,
+                            upstream.as_ref(),
+                            &pending,
+                            &next_id,
+                            &id_range,
+                            &zones,
+                            &cache,
+                            &handler,
+                            &metrics,
+                            upstream_timeout,
+                        ) {
+                            // Connection closed or malformed; nothing else to do.
+                        }
+                    });
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    thread::sleep(Duration::from_millis(100));
+                }
+                Err(_e) => thread::sleep(Duration::from_millis(100)),
+            }
+        }
+    }
+
+    /// Serves every length-prefixed query sent on a single TCP connection, trying
+    /// the local zones, then the custom handler, then the cache, before forwarding upstream.
+    #[allow(clippy::too_many_arguments)]
+    fn serve(
+        mut stream: TcpStream,
+        upstream: &dyn UpstreamResolver,
+        pending: &PendingReplies,
+        next_id: &AtomicU16,
+        id_range: &Range<u16>,
+        zones: &ZoneStore,
+        cache: &Option<ThreadSafeCache>,
+        handler: &HandlerHolder,
+        metrics: &Metrics,
+        upstream_timeout: Duration,
+    ) -> io::Result<()> {
+        loop {
+            let query = read_tcp_message(&mut stream)?;
+            metrics.record_query_received();
+
+            let reply = if let Some(reply) = zone_reply(zones, &query) {
+                reply
+            } else if let Ok(reply) = handler.handle(&query) {
+                metrics.record_handler_hit();
+                reply
+            } else {
+                metrics.record_handler_error();
+
+                if let Some(reply) = cached_reply(cache, &query) {
+                    metrics.record_cache_hit();
+                    reply
+                } else {
+                    if cache.is_some() {
+                        metrics.record_cache_miss();
+                    }
+
+                    let id = Self::next_id(next_id, id_range);
+                    let reply = Self::forward(upstream, pending, metrics, upstream_timeout, &query, id);
+                    cache_reply_if_present(cache, &query, &reply);
+                    reply
+                }
+            };
+
+            let len = (reply.len() as u16).to_be_bytes();
+            stream.write_all(&len)?;
+            stream.write_all(&reply)?;
+        }
+    }
+
+    /// Forwards `query` upstream under `id`, waiting up to `upstream_timeout` for a
+    /// matching reply before giving up with SERVFAIL.
+    fn forward(
+        upstream: &dyn UpstreamResolver,
+        pending: &PendingReplies,
+        metrics: &Metrics,
+        upstream_timeout: Duration,
+        query: &[u8],
+        id: u16,
+    ) -> Vec<u8> {
+        let (reply_sender, reply_receiver) = channel();
+        pending.lock().unwrap().insert(id, reply_sender);
+
+        let mut forwarded = query.to_vec();
+        let id_bytes = id.to_be_bytes();
+        if forwarded.len() >= 2 {
+            forwarded[0] = id_bytes[0];
+            forwarded[1] = id_bytes[1];
+        }
+
+        if upstream.send(&forwarded).is_err() {
+            pending.lock().unwrap().remove(&id);
+            return servfail_reply(query);
+        }
+        metrics.record_upstream_forward();
+
+        match reply_receiver.recv_timeout(upstream_timeout) {
+            Ok(reply) => with_original_id(&reply, query),
+            Err(_) => {
+                pending.lock().unwrap().remove(&id);
+                metrics.record_upstream_timeout();
+                servfail_reply(query)
+            }
+        }
+    }
+
+    /// Picks the next id out of `range`, wrapping around once it's exhausted.
+    /// Shared by every connection thread via the same [`AtomicU16`], so concurrent
+    /// connections hand out distinct ids instead of racing to reuse one.
+    fn next_id(next_id: &AtomicU16, range: &Range<u16>) -> u16 {
+        let id = next_id.fetch_add(1, Ordering::Relaxed);
+        range.start + (id.wrapping_sub(range.start) % (range.end - range.start).max(1))
+    }
+
+    pub fn stop(&mut self) {
+        let _ = self.stop_signal_sender.send(());
+    }
+
+    pub fn join(mut self) {
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Stores `reply`'s answers for the question in `original_query`, if `reply` parses
+/// and caching is enabled.
+fn cache_reply_if_present(cache: &Option<ThreadSafeCache>, original_query: &[u8], reply: &[u8]) {
+    if let Ok(parsed) = Packet::parse(reply) {
+        cache_reply(cache, original_query, &parsed);
+    }
+}
+
+impl fmt::Debug for TcpDnsListener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TcpDnsListener").finish()
+    }
+}
@@ -0,0 +1,634 @@
+//! Pluggable "send a query to ICANN and get a reply" step. The default is
+//! plain UDP, but [`Upstream::Doh`] and [`Upstream::Dot`] send the same
+//! queries over an encrypted transport instead. Every implementation rewrites
+//! nothing about id correlation: replies are simply pushed back, by whichever
+//! means, onto the channel handed to [`Upstream::build`], and the caller
+//! matches them to a [`crate::pending_queries::PendingQuery`] by DNS id exactly
+//! as it would for a reply read straight off a UDP socket.
+
+use std::{
+    collections::HashMap,
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpStream, UdpSocket},
+    sync::{
+        atomic::{AtomicU32, AtomicUsize, Ordering},
+        mpsc::{channel, Sender},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+use simple_dns::{Packet, PacketFlag};
+
+use crate::tcp;
+
+/// Consecutive timeouts a resolver must rack up before [`MultiUdpUpstream`] stops
+/// picking it until [`UNHEALTHY_COOLDOWN`] has passed.
+const UNHEALTHY_THRESHOLD: u32 = 3;
+/// How long a resolver is skipped after being marked unhealthy.
+const UNHEALTHY_COOLDOWN: Duration = Duration::from_secs(30);
+/// Delay before [`DotUpstream`]'s first reconnect attempt after its connection drops.
+const INITIAL_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+/// Upper bound the reconnect delay backs off to, doubling each failed attempt.
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+/// Sends queries to the upstream ICANN resolver. Implementations own whatever
+/// connection/socket the transport needs and deliver replies asynchronously
+/// through the channel they were constructed with, rather than returning them
+/// from `send`.
+pub trait UpstreamResolver: Send + Sync {
+    /// Sends `query` upstream. Only errors that prevent the query from being
+    /// sent at all are returned here; a reply (or its absence) shows up later
+    /// on the reply channel.
+    fn send(&self, query: &[u8]) -> io::Result<()>;
+}
+
+/// Selects where and how to reach the upstream ICANN resolver.
+#[derive(Debug, Clone)]
+pub enum Upstream {
+    /// Plain UDP to `icann_resolver` (the default).
+    Udp(SocketAddr),
+    /// Plain UDP to a pool of resolvers: `preferred` is round-robined first, and
+    /// `fallback` is only used once every preferred server has been tried for a
+    /// given query. A resolver that times out repeatedly is skipped for a while.
+    UdpPool {
+        preferred: Vec<SocketAddr>,
+        fallback: Vec<SocketAddr>,
+    },
+    /// DNS-over-HTTPS: POST the query to this `https://` endpoint, e.g.
+    /// `https://dns.google/dns-query`.
+    Doh(String),
+    /// DNS-over-TLS: a persistent TLS connection to `addr`, whose certificate
+    /// is validated against `server_name`.
+    Dot { addr: SocketAddr, server_name: String },
+}
+
+impl Upstream {
+    /// Builds the resolver for this upstream, pushing every reply it receives onto `replies`.
+    /// `upstream_timeout` is the same total-deadline the caller enforces on a pending
+    /// query, and is used by [`MultiUdpUpstream`] to tell a retransmit of an existing
+    /// query apart from a brand new query that happens to reuse the same DNS id.
+    pub fn build(&self, replies: Sender<Vec<u8>>, upstream_timeout: Duration) -> io::Result<Box<dyn UpstreamResolver>> {
+        match self {
+            Upstream::Udp(addr) => Ok(Box::new(UdpUpstream::new(*addr, replies)?)),
+            Upstream::UdpPool { preferred, fallback } => Ok(Box::new(MultiUdpUpstream::new(
+                preferred.clone(),
+                fallback.clone(),
+                upstream_timeout,
+                replies,
+            )?)),
+            Upstream::Doh(endpoint) => Ok(Box::new(DohUpstream::new(endpoint, replies)?)),
+            Upstream::Dot { addr, server_name } => {
+                Ok(Box::new(DotUpstream::new(*addr, server_name.clone(), replies)?))
+            }
+        }
+    }
+}
+
+/// The original transport: a connected UDP socket, shared by everything that
+/// holds this resolver, with a background reader forwarding datagrams onto the
+/// reply channel. Truncated replies are transparently retried over TCP.
+struct UdpUpstream {
+    socket: UdpSocket,
+}
+
+impl UdpUpstream {
+    fn new(icann_resolver: SocketAddr, replies: Sender<Vec<u8>>) -> io::Result<Self> {
+        let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+        socket.connect(icann_resolver)?;
+        let reader = socket.try_clone()?;
+
+        thread::spawn(move || {
+            let mut buffer = [0u8; 4096];
+            while let Ok(size) = reader.recv(&mut buffer) {
+                let reply = follow_truncation(icann_resolver, &buffer[..size]);
+                if replies.send(reply).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Self { socket })
+    }
+}
+
+impl UpstreamResolver for UdpUpstream {
+    fn send(&self, query: &[u8]) -> io::Result<()> {
+        self.socket.send(query).map(|_| ())
+    }
+}
+
+/// If `packet` is a truncated reply, re-issues its question to `resolver` over TCP
+/// to get the full answer. Shared by every plain-UDP transport ([`UdpUpstream`] and
+/// [`MultiUdpUpstream`]), each of which knows which resolver to retry against.
+fn follow_truncation(resolver: SocketAddr, packet: &[u8]) -> Vec<u8> {
+    let Ok(parsed) = Packet::parse(packet) else {
+        return packet.to_vec();
+    };
+    if !parsed.has_flags(PacketFlag::TRUNCATION) {
+        return packet.to_vec();
+    }
+
+    let mut retry_query = Packet::new_query(parsed.id());
+    retry_query.questions = parsed.questions;
+    let Ok(retry_query) = retry_query.build_bytes_vec() else {
+        return packet.to_vec();
+    };
+
+    tcp::send_tcp_query(resolver, &retry_query).unwrap_or_else(|_| packet.to_vec())
+}
+
+/// A single resolver address in a [`MultiUdpUpstream`] pool, with lightweight health tracking.
+struct ResolverSlot {
+    addr: SocketAddr,
+    consecutive_timeouts: AtomicU32,
+    unhealthy_until: Mutex<Option<Instant>>,
+}
+
+impl ResolverSlot {
+    fn new(addr: SocketAddr) -> Self {
+        Self {
+            addr,
+            consecutive_timeouts: AtomicU32::new(0),
+            unhealthy_until: Mutex::new(None),
+        }
+    }
+
+    fn is_healthy(&self) -> bool {
+        match *self.unhealthy_until.lock().unwrap() {
+            Some(until) => Instant::now() >= until,
+            None => true,
+        }
+    }
+
+    fn record_timeout(&self) {
+        let timeouts = self.consecutive_timeouts.fetch_add(1, Ordering::Relaxed) + 1;
+        if timeouts >= UNHEALTHY_THRESHOLD {
+            *self.unhealthy_until.lock().unwrap() = Some(Instant::now() + UNHEALTHY_COOLDOWN);
+        }
+    }
+
+    fn record_success(&self) {
+        self.consecutive_timeouts.store(0, Ordering::Relaxed);
+        *self.unhealthy_until.lock().unwrap() = None;
+    }
+}
+
+/// One query's progress through a [`MultiUdpUpstream`] pool: which resolver it was
+/// last sent to, how many resolvers have been tried so far, and when.
+struct Attempt {
+    count: u32,
+    addr: SocketAddr,
+    sent_at: Instant,
+}
+
+/// State shared between [`MultiUdpUpstream`] and its background reader thread.
+struct ResolverPool {
+    preferred: Vec<ResolverSlot>,
+    fallback: Vec<ResolverSlot>,
+    next_preferred: AtomicUsize,
+    next_fallback: AtomicUsize,
+    attempts: Mutex<HashMap<u16, Attempt>>,
+    upstream_timeout: Duration,
+}
+
+impl ResolverPool {
+    /// Picks the resolver for this attempt at `id`, recording that it was tried. If
+    /// a send for `id` already happened recently enough that it must be the same
+    /// query retrying (rather than a later query that reused the same 16-bit id),
+    /// the previous resolver is charged with a timeout before moving on.
+    fn choose(&self, id: u16) -> SocketAddr {
+        let mut attempts = self.attempts.lock().unwrap();
+        let now = Instant::now();
+
+        let previous = attempts
+            .get(&id)
+            .filter(|attempt| now.duration_since(attempt.sent_at) < self.upstream_timeout);
+
+        let attempt_count = match previous {
+            Some(attempt) => {
+                self.slot(attempt.addr).record_timeout();
+                attempt.count
+            }
+            None => 0,
+        };
+
+        let addr = self.pick(attempt_count);
+        attempts.insert(
+            id,
+            Attempt {
+                count: attempt_count + 1,
+                addr,
+                sent_at: now,
+            },
+        );
+        addr
+    }
+
+    /// Picks the next resolver for an attempt: round-robins the preferred set until
+    /// every preferred server has had a turn, then falls back to the fallback set.
+    fn pick(&self, attempt_count: u32) -> SocketAddr {
+        if (attempt_count as usize) < self.preferred.len() {
+            if let Some(addr) = self.pick_from(&self.preferred, &self.next_preferred) {
+                return addr;
+            }
+        }
+
+        self.pick_from(&self.fallback, &self.next_fallback)
+            .or_else(|| self.pick_from(&self.preferred, &self.next_preferred))
+            .expect("at least one ICANN resolver must be configured")
+    }
+
+    /// Round-robins across the healthy slots in `slots`, falling back to every slot
+    /// (even unhealthy ones) if none are currently healthy.
+    fn pick_from(&self, slots: &[ResolverSlot], cursor: &AtomicUsize) -> Option<SocketAddr> {
+        if slots.is_empty() {
+            return None;
+        }
+
+        let healthy: Vec<&ResolverSlot> = slots.iter().filter(|slot| slot.is_healthy()).collect();
+        let pool = if healthy.is_empty() { slots.iter().collect() } else { healthy };
+        let index = cursor.fetch_add(1, Ordering::Relaxed) % pool.len();
+        Some(pool[index].addr)
+    }
+
+    fn slot(&self, addr: SocketAddr) -> &ResolverSlot {
+        self.preferred
+            .iter()
+            .chain(self.fallback.iter())
+            .find(|slot| slot.addr == addr)
+            .expect("addr was returned by pick() and must belong to this pool")
+    }
+
+    /// Marks the resolver that answered `id` from `from` as healthy again, and
+    /// forgets this query's in-flight attempt.
+    fn record_reply(&self, id: u16, from: SocketAddr) {
+        if let Some(slot) = self
+            .preferred
+            .iter()
+            .chain(self.fallback.iter())
+            .find(|slot| slot.addr == from)
+        {
+            slot.record_success();
+        }
+        self.attempts.lock().unwrap().remove(&id);
+    }
+}
+
+/// Round-robins queries across a preferred set of ICANN resolvers, falling over to
+/// a fallback set once the preferred set has been tried, and temporarily skipping
+/// resolvers that keep timing out. See [`Upstream::UdpPool`].
+struct MultiUdpUpstream {
+    socket: UdpSocket,
+    pool: Arc<ResolverPool>,
+}
+
+impl MultiUdpUpstream {
+    fn new(
+        preferred: Vec<SocketAddr>,
+        fallback: Vec<SocketAddr>,
+        upstream_timeout: Duration,
+        replies: Sender<Vec<u8>>,
+    ) -> io::Result<Self> {
+        let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+        let reader = socket.try_clone()?;
+
+        let pool = Arc::new(ResolverPool {
+            preferred: preferred.into_iter().map(ResolverSlot::new).collect(),
+            fallback: fallback.into_iter().map(ResolverSlot::new).collect(),
+            next_preferred: AtomicUsize::new(0),
+            next_fallback: AtomicUsize::new(0),
+            attempts: Mutex::new(HashMap::new()),
+            upstream_timeout,
+        });
+
+        let reader_pool = pool.clone();
+        thread::spawn(move || {
+            let mut buffer = [0u8; 4096];
+            while let Ok((size, from)) = reader.recv_from(&mut buffer) {
+                let packet = &buffer[..size];
+                if let Ok(parsed) = Packet::parse(packet) {
+                    reader_pool.record_reply(parsed.id(), from);
+                }
+                let reply = follow_truncation(from, packet);
+                if replies.send(reply).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Self { socket, pool })
+    }
+}
+
+impl UpstreamResolver for MultiUdpUpstream {
+    fn send(&self, query: &[u8]) -> io::Result<()> {
+        let Some(id_bytes) = query.get(0..2) else {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "query too short to contain a DNS id"));
+        };
+        let id = u16::from_be_bytes([id_bytes[0], id_bytes[1]]);
+        let addr = self.pool.choose(id);
+        self.socket.send_to(query, addr).map(|_| ())
+    }
+}
+
+/// DNS-over-HTTPS: each query is POSTed as `application/dns-message` over its
+/// own short-lived TLS connection, which keeps this implementation simple at
+/// the cost of a fresh TLS handshake per query.
+struct DohUpstream {
+    host: String,
+    port: u16,
+    path: String,
+    tls_config: Arc<ClientConfig>,
+    replies: Sender<Vec<u8>>,
+}
+
+impl DohUpstream {
+    fn new(endpoint: &str, replies: Sender<Vec<u8>>) -> io::Result<Self> {
+        let (host, port, path) = Self::parse_endpoint(endpoint)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid DoH endpoint, expected https://host[:port]/path"))?;
+
+        Ok(Self {
+            host,
+            port,
+            path,
+            tls_config: Arc::new(tls_config_with_webpki_roots()),
+            replies,
+        })
+    }
+
+    fn parse_endpoint(endpoint: &str) -> Option<(String, u16, String)> {
+        let rest = endpoint.strip_prefix("https://")?;
+        let (authority, path) = match rest.split_once('/') {
+            Some((authority, path)) => (authority, format!("/{path}")),
+            None => (rest, "/".to_string()),
+        };
+        let (host, port) = match authority.split_once(':') {
+            Some((host, port)) => (host.to_string(), port.parse().ok()?),
+            None => (authority.to_string(), 443),
+        };
+        Some((host, port, path))
+    }
+
+    /// Opens a fresh TLS connection, POSTs `query` and returns the response body.
+    fn post(host: &str, port: u16, path: &str, tls_config: &Arc<ClientConfig>, query: &[u8]) -> io::Result<Vec<u8>> {
+        let server_name = host
+            .to_string()
+            .try_into()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid DoH host"))?;
+        let connection = ClientConnection::new(tls_config.clone(), server_name)
+            .map_err(io::Error::other)?;
+        let stream = TcpStream::connect((host, port))?;
+        let mut tls = StreamOwned::new(connection, stream);
+
+        let request = format!(
+            "POST {path} HTTP/1.1\r\n\
+             Host: {host}\r\n\
+             Content-Type: application/dns-message\r\n\
+             Accept: application/dns-message\r\n\
+             Content-Length: {}\r\n\
+             Connection: close\r\n\r\n",
+            query.len()
+        );
+        tls.write_all(request.as_bytes())?;
+        tls.write_all(query)?;
+
+        let mut response = Vec::new();
+        tls.read_to_end(&mut response)?;
+
+        let header_end = response
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed DoH response: no header terminator"))?;
+        Ok(response[header_end + 4..].to_vec())
+    }
+}
+
+impl UpstreamResolver for DohUpstream {
+    fn send(&self, query: &[u8]) -> io::Result<()> {
+        let host = self.host.clone();
+        let port = self.port;
+        let path = self.path.clone();
+        let tls_config = self.tls_config.clone();
+        let replies = self.replies.clone();
+        let query = query.to_vec();
+
+        thread::spawn(move || {
+            if let Ok(reply) = Self::post(&host, port, &path, &tls_config, &query) {
+                let _ = replies.send(reply);
+            }
+        });
+
+        Ok(())
+    }
+}
+
+/// DNS-over-TLS: queries are length-prefixed (RFC 1035 §4.2.2) over a single
+/// persistent TLS connection, mirroring plain DNS-over-TCP in [`crate::tcp`].
+/// A dedicated thread owns the connection, alternately flushing outgoing
+/// queries and polling for replies, so `send` never blocks on a read. The
+/// connection is transparently re-established, with backoff, if it ever drops.
+struct DotUpstream {
+    queries: Sender<Vec<u8>>,
+}
+
+impl DotUpstream {
+    fn new(addr: SocketAddr, server_name: String, replies: Sender<Vec<u8>>) -> io::Result<Self> {
+        let tls_config = Arc::new(tls_config_with_webpki_roots());
+        let mut connection = Self::connect(addr, &server_name, &tls_config)?;
+        connection.sock.set_read_timeout(Some(Duration::from_millis(200)))?;
+        let (queries, incoming_queries) = channel::<Vec<u8>>();
+
+        thread::spawn(move || {
+            let mut reconnect_delay = INITIAL_RECONNECT_DELAY;
+            let mut read_buf: Vec<u8> = Vec::new();
+
+            loop {
+                while let Ok(query) = incoming_queries.try_recv() {
+                    let length = (query.len() as u16).to_be_bytes();
+                    if connection.write_all(&length).is_err() || connection.write_all(&query).is_err() {
+                        connection = Self::reconnect(addr, &server_name, &tls_config, &mut reconnect_delay);
+                        read_buf.clear();
+                        break;
+                    }
+                }
+
+                match Self::read_message(&mut connection, &mut read_buf) {
+                    Ok(Some(reply)) => {
+                        if replies.send(reply).is_err() {
+                            return;
+                        }
+                    }
+                    Ok(None) => continue,
+                    Err(_) => {
+                        connection = Self::reconnect(addr, &server_name, &tls_config, &mut reconnect_delay);
+                        read_buf.clear();
+                    }
+                }
+            }
+        });
+
+        Ok(Self { queries })
+    }
+
+    fn connect(addr: SocketAddr, server_name: &str, tls_config: &Arc<ClientConfig>) -> io::Result<StreamOwned<ClientConnection, TcpStream>> {
+        let connection = ClientConnection::new(
+            tls_config.clone(),
+            server_name
+                .to_string()
+                .try_into()
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid DoT server name"))?,
+        )
+        .map_err(io::Error::other)?;
+        let stream = TcpStream::connect(addr)?;
+        Ok(StreamOwned::new(connection, stream))
+    }
+
+    /// Retries [`Self::connect`] with doubling backoff until it succeeds, since the
+    /// DoT server dropping the connection shouldn't permanently kill this upstream.
+    fn reconnect(
+        addr: SocketAddr,
+        server_name: &str,
+        tls_config: &Arc<ClientConfig>,
+        delay: &mut Duration,
+    ) -> StreamOwned<ClientConnection, TcpStream> {
+        loop {
+            thread::sleep(*delay);
+            *delay = delay.saturating_mul(2).min(MAX_RECONNECT_DELAY);
+
+            if let Ok(connection) = Self::connect(addr, server_name, tls_config) {
+                if connection.sock.set_read_timeout(Some(Duration::from_millis(200))).is_ok() {
+                    *delay = INITIAL_RECONNECT_DELAY;
+                    return connection;
+                }
+            }
+        }
+    }
+
+    /// Reads whatever bytes are currently available into `buf` and returns a
+    /// complete length-prefixed message once `buf` holds one, leaving any
+    /// leftover bytes buffered for the next call. A read timing out before a
+    /// full message has arrived is not an error: the partial bytes already in
+    /// `buf` stay put instead of being discarded and desyncing the framing.
+    fn read_message(stream: &mut StreamOwned<ClientConnection, TcpStream>, buf: &mut Vec<u8>) -> io::Result<Option<Vec<u8>>> {
+        let mut chunk = [0u8; 4096];
+        match stream.read(&mut chunk) {
+            Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "DoT connection closed")),
+            Ok(n) => buf.extend_from_slice(&chunk[..n]),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => {}
+            Err(e) => return Err(e),
+        }
+
+        if buf.len() < 2 {
+            return Ok(None);
+        }
+        let len = u16::from_be_bytes([buf[0], buf[1]]) as usize;
+        if buf.len() < 2 + len {
+            return Ok(None);
+        }
+
+        let message = buf[2..2 + len].to_vec();
+        buf.drain(..2 + len);
+        Ok(Some(message))
+    }
+}
+
+impl UpstreamResolver for DotUpstream {
+    fn send(&self, query: &[u8]) -> io::Result<()> {
+        self.queries
+            .send(query.to_vec())
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "DoT connection thread has exited"))
+    }
+}
+
+fn tls_config_with_webpki_roots() -> ClientConfig {
+    let mut root_store = RootCertStore::empty();
+    root_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    ClientConfig::builder()
+        .with_root_certificates(root_store)
+        .with_no_client_auth()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    fn pool(preferred: &[u16], fallback: &[u16]) -> ResolverPool {
+        ResolverPool {
+            preferred: preferred.iter().map(|port| ResolverSlot::new(addr(*port))).collect(),
+            fallback: fallback.iter().map(|port| ResolverSlot::new(addr(*port))).collect(),
+            next_preferred: AtomicUsize::new(0),
+            next_fallback: AtomicUsize::new(0),
+            attempts: Mutex::new(HashMap::new()),
+            upstream_timeout: Duration::from_secs(10),
+        }
+    }
+
+    #[test]
+    fn pick_round_robins_the_preferred_set_first() {
+        let pool = pool(&[1, 2], &[3]);
+        assert_eq!(pool.pick(0), addr(1));
+        assert_eq!(pool.pick(0), addr(2));
+        assert_eq!(pool.pick(0), addr(1));
+    }
+
+    #[test]
+    fn pick_falls_over_to_fallback_once_every_preferred_server_was_tried() {
+        let pool = pool(&[1, 2], &[3]);
+        assert_eq!(pool.pick(2), addr(3));
+    }
+
+    #[test]
+    fn pick_uses_preferred_set_when_fallback_is_empty() {
+        let pool = pool(&[1], &[]);
+        assert_eq!(pool.pick(5), addr(1));
+    }
+
+    #[test]
+    fn pick_skips_unhealthy_resolvers_until_all_are_unhealthy() {
+        let pool = pool(&[1, 2], &[]);
+        for _ in 0..UNHEALTHY_THRESHOLD {
+            pool.slot(addr(1)).record_timeout();
+        }
+
+        for _ in 0..4 {
+            assert_eq!(pool.pick(0), addr(2), "unhealthy resolver should be skipped");
+        }
+    }
+
+    #[test]
+    fn choose_assigns_a_fresh_id_its_first_attempt() {
+        let pool = pool(&[1, 2], &[]);
+        let picked = pool.choose(42);
+        assert!(picked == addr(1) || picked == addr(2));
+        assert_eq!(pool.attempts.lock().unwrap().get(&42).unwrap().count, 1);
+    }
+
+    #[test]
+    fn choose_charges_the_previous_resolver_with_a_timeout_on_retry() {
+        let pool = pool(&[1], &[]);
+        let first = pool.choose(7);
+        assert_eq!(pool.slot(first).consecutive_timeouts.load(Ordering::Relaxed), 0);
+
+        pool.choose(7);
+        assert_eq!(pool.slot(first).consecutive_timeouts.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn record_reply_clears_the_attempt_and_marks_the_resolver_healthy() {
+        let pool = pool(&[1], &[]);
+        pool.choose(9);
+        pool.slot(addr(1)).record_timeout();
+
+        pool.record_reply(9, addr(1));
+
+        assert!(pool.attempts.lock().unwrap().get(&9).is_none());
+        assert_eq!(pool.slot(addr(1)).consecutive_timeouts.load(Ordering::Relaxed), 0);
+    }
+}
@@ -0,0 +1,247 @@
+//! Local authoritative zones: suffixes any-dns answers for directly, without
+//! consulting the custom handler or `icann_resolver`.
+
+use std::{collections::HashMap, sync::Arc};
+
+use simple_dns::{Question, ResourceRecord, TYPE, QTYPE};
+
+/// What a [`Zone`] says about a question that falls under its suffix.
+#[derive(Debug, Clone)]
+pub enum ZoneAnswer {
+    /// NOERROR: these records answer the question directly.
+    Found(Vec<ResourceRecord<'static>>),
+    /// NOERROR, but the name doesn't have records of the requested type.
+    /// `soa` should be placed in the authority section.
+    NoData { soa: ResourceRecord<'static> },
+    /// NXDOMAIN: the name doesn't exist in the zone.
+    /// `soa` should be placed in the authority section.
+    NxDomain { soa: ResourceRecord<'static> },
+}
+
+/// A locally-authoritative zone for a single suffix (e.g. `pkarr`), built once
+/// and answered from directly instead of falling through to `icann_resolver`.
+#[derive(Debug, Clone)]
+pub struct Zone {
+    /// Lowercased suffix this zone is authoritative for, without a trailing dot.
+    suffix: String,
+    /// SOA record returned in the authority section of negative answers.
+    soa: ResourceRecord<'static>,
+    /// Records in this zone, keyed by lowercased owner name and record type.
+    records: HashMap<(String, TYPE), Vec<ResourceRecord<'static>>>,
+}
+
+impl Zone {
+    /// Creates a new zone authoritative for `suffix` (e.g. `"pkarr"` or `"example.com"`).
+    /// `soa` is returned in the authority section of NOERROR/NXDOMAIN negative answers.
+    pub fn new(suffix: &str, soa: ResourceRecord<'static>) -> Self {
+        Self {
+            suffix: suffix.trim_end_matches('.').to_lowercase(),
+            soa,
+            records: HashMap::new(),
+        }
+    }
+
+    /// Adds a record to this zone. Its owner name doesn't need to equal the suffix
+    /// itself, as long as it falls under it (e.g. `www.example.com` under `example.com`).
+    pub fn record(mut self, record: ResourceRecord<'static>) -> Self {
+        let key = (record.name.to_string().to_lowercase(), record.rdata.type_code());
+        self.records.entry(key).or_default().push(record);
+        self
+    }
+
+    /// Whether `qname` falls under this zone's suffix.
+    fn covers(&self, qname: &str) -> bool {
+        qname == self.suffix || qname.ends_with(&format!(".{}", self.suffix))
+    }
+
+    /// Answers `question`, assuming it already falls under this zone's suffix.
+    fn answer(&self, question: &Question) -> ZoneAnswer {
+        let qname = question.qname.to_string().to_lowercase();
+
+        let matched: Vec<ResourceRecord<'static>> = match question.qtype {
+            QTYPE::ANY => self
+                .records
+                .iter()
+                .filter(|((name, _), _)| name == &qname)
+                .flat_map(|(_, records)| records.clone())
+                .collect(),
+            QTYPE::TYPE(ty) => self
+                .records
+                .get(&(qname.clone(), ty))
+                .cloned()
+                .unwrap_or_default(),
+            _ => Vec::new(),
+        };
+
+        if !matched.is_empty() {
+            return ZoneAnswer::Found(matched);
+        }
+
+        // A CNAME at `qname` answers any query type other than CNAME/ANY itself,
+        // so the resolver can chase it, per RFC 1034 section 3.6.2.
+        if !matches!(question.qtype, QTYPE::TYPE(TYPE::CNAME) | QTYPE::ANY) {
+            if let Some(cname) = self.records.get(&(qname.clone(), TYPE::CNAME)) {
+                return ZoneAnswer::Found(cname.clone());
+            }
+        }
+
+        let name_exists = self.records.keys().any(|(name, _)| name == &qname);
+        if name_exists {
+            ZoneAnswer::NoData { soa: self.soa.clone() }
+        } else {
+            ZoneAnswer::NxDomain { soa: self.soa.clone() }
+        }
+    }
+}
+
+/// Suffix-indexed, read-only collection of [`Zone`]s, built once in [`crate::Builder::build`]
+/// and shared across every [`crate::dns_thread::DnsThread`].
+#[derive(Debug, Clone)]
+pub struct ZoneStore {
+    zones: Arc<HashMap<String, Zone>>,
+}
+
+impl ZoneStore {
+    pub fn new(zones: Vec<Zone>) -> Self {
+        Self {
+            zones: Arc::new(zones.into_iter().map(|zone| (zone.suffix.clone(), zone)).collect()),
+        }
+    }
+
+    /// Answers `question` from the most specific zone covering it, if any is configured.
+    pub fn answer(&self, question: &Question) -> Option<ZoneAnswer> {
+        let qname = question.qname.to_string().to_lowercase();
+
+        // Strip leading labels one at a time, so the longest matching suffix wins.
+        let mut candidate = qname.as_str();
+        loop {
+            if let Some(zone) = self.zones.get(candidate) {
+                return Some(zone.answer(question));
+            }
+            candidate = candidate.split_once('.')?.1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simple_dns::rdata::{CNAME, SOA};
+    use simple_dns::{Name, QCLASS, CLASS};
+
+    fn soa(name: &str) -> ResourceRecord<'static> {
+        ResourceRecord::new(
+            Name::new(name).unwrap().into_owned(),
+            CLASS::IN,
+            3600,
+            simple_dns::rdata::RData::SOA(SOA {
+                mname: Name::new("ns1.example.com").unwrap().into_owned(),
+                rname: Name::new("hostmaster.example.com").unwrap().into_owned(),
+                serial: 1,
+                refresh: 1,
+                retry: 1,
+                expire: 1,
+                minimum: 60,
+            }),
+        )
+    }
+
+    fn question(name: &str, qtype: QTYPE) -> Question<'static> {
+        Question::new(Name::new(name).unwrap().into_owned(), qtype, QCLASS::CLASS(CLASS::IN), false)
+    }
+
+    #[test]
+    fn found_when_a_matching_record_exists() {
+        let record = ResourceRecord::new(
+            Name::new("www.example.com").unwrap().into_owned(),
+            CLASS::IN,
+            300,
+            simple_dns::rdata::RData::A(simple_dns::rdata::A { address: std::net::Ipv4Addr::new(1, 2, 3, 4).into() }),
+        );
+        let zone = Zone::new("example.com", soa("example.com")).record(record);
+
+        let answer = zone.answer(&question("www.example.com", QTYPE::TYPE(TYPE::A)));
+        assert!(matches!(answer, ZoneAnswer::Found(records) if records.len() == 1));
+    }
+
+    #[test]
+    fn cname_answers_a_non_cname_non_any_query() {
+        let cname = ResourceRecord::new(
+            Name::new("www.example.com").unwrap().into_owned(),
+            CLASS::IN,
+            300,
+            simple_dns::rdata::RData::CNAME(CNAME(Name::new("target.example.com").unwrap().into_owned())),
+        );
+        let zone = Zone::new("example.com", soa("example.com")).record(cname);
+
+        let answer = zone.answer(&question("www.example.com", QTYPE::TYPE(TYPE::A)));
+        assert!(matches!(answer, ZoneAnswer::Found(records) if records.len() == 1));
+    }
+
+    #[test]
+    fn cname_and_any_queries_get_the_cname_record_itself() {
+        let cname = ResourceRecord::new(
+            Name::new("www.example.com").unwrap().into_owned(),
+            CLASS::IN,
+            300,
+            simple_dns::rdata::RData::CNAME(CNAME(Name::new("target.example.com").unwrap().into_owned())),
+        );
+        let zone = Zone::new("example.com", soa("example.com")).record(cname);
+
+        assert!(matches!(
+            zone.answer(&question("www.example.com", QTYPE::TYPE(TYPE::CNAME))),
+            ZoneAnswer::Found(_)
+        ));
+        assert!(matches!(zone.answer(&question("www.example.com", QTYPE::ANY)), ZoneAnswer::Found(_)));
+    }
+
+    #[test]
+    fn nodata_when_the_name_exists_but_not_for_the_requested_type() {
+        let record = ResourceRecord::new(
+            Name::new("www.example.com").unwrap().into_owned(),
+            CLASS::IN,
+            300,
+            simple_dns::rdata::RData::A(simple_dns::rdata::A { address: std::net::Ipv4Addr::new(1, 2, 3, 4).into() }),
+        );
+        let zone = Zone::new("example.com", soa("example.com")).record(record);
+
+        let answer = zone.answer(&question("www.example.com", QTYPE::TYPE(TYPE::AAAA)));
+        assert!(matches!(answer, ZoneAnswer::NoData { .. }));
+    }
+
+    #[test]
+    fn nxdomain_when_the_name_does_not_exist_in_the_zone() {
+        let zone = Zone::new("example.com", soa("example.com"));
+
+        let answer = zone.answer(&question("missing.example.com", QTYPE::TYPE(TYPE::A)));
+        assert!(matches!(answer, ZoneAnswer::NxDomain { .. }));
+    }
+
+    #[test]
+    fn zone_store_picks_the_most_specific_covering_zone() {
+        let outer = Zone::new("example.com", soa("example.com")).record(ResourceRecord::new(
+            Name::new("www.example.com").unwrap().into_owned(),
+            CLASS::IN,
+            300,
+            simple_dns::rdata::RData::A(simple_dns::rdata::A { address: std::net::Ipv4Addr::new(1, 1, 1, 1).into() }),
+        ));
+        let inner = Zone::new("sub.example.com", soa("sub.example.com")).record(ResourceRecord::new(
+            Name::new("www.sub.example.com").unwrap().into_owned(),
+            CLASS::IN,
+            300,
+            simple_dns::rdata::RData::A(simple_dns::rdata::A { address: std::net::Ipv4Addr::new(2, 2, 2, 2).into() }),
+        ));
+        let store = ZoneStore::new(vec![outer, inner]);
+
+        let answer = store.answer(&question("www.sub.example.com", QTYPE::TYPE(TYPE::A))).expect("should be covered");
+        assert!(matches!(answer, ZoneAnswer::Found(records) if records.len() == 1));
+    }
+
+    #[test]
+    fn zone_store_returns_none_for_an_uncovered_name() {
+        let zone = Zone::new("example.com", soa("example.com"));
+        let store = ZoneStore::new(vec![zone]);
+
+        assert!(store.answer(&question("other.org", QTYPE::TYPE(TYPE::A))).is_none());
+    }
+}